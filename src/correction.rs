@@ -1,8 +1,5 @@
 use crate::{Gain, IntegrationTime};
 
-#[cfg(feature = "lux_as_f32")]
-use micromath::F32Ext;
-
 /// Calculate raw value for threshold applying compensation if necessary.
 ///
 /// For values higher than 1000 lx and 1/4 or 1/8 gain, the inverse of the
@@ -36,112 +33,248 @@ pub(crate) fn get_lux_raw_conversion_factor(it: IntegrationTime, gain: Gain) ->
     gain_factor * it_factor
 }
 
+#[cfg(not(feature = "correction_f64"))]
 const C0: f32 = 1.0023;
+#[cfg(not(feature = "correction_f64"))]
 const C1: f32 = 8.1488e-05;
+#[cfg(not(feature = "correction_f64"))]
 const C2: f32 = -9.3924e-09;
+#[cfg(not(feature = "correction_f64"))]
 const C3: f32 = 6.0135e-13;
 
+/// Evaluated in Horner's form: `(((C3*x + C2)*x + C1)*x + C0)*x`. This is
+/// three multiply-adds and no `powf`, against the original two
+/// transcendental `powf` calls, which matters on FPU-less targets.
+#[cfg(not(feature = "correction_f64"))]
+pub(crate) fn correct_high_lux(lux: f32) -> f32 {
+    (((C3 * lux + C2) * lux + C1) * lux + C0) * lux
+}
+
+/// f64 counterpart of [`correct_high_lux`].
+///
+/// The f32 evaluation of the quartic above is prone to losing precision in
+/// its higher-order terms (`C3` alone is on the order of `1e-13`), so when
+/// the `correction_f64` feature is enabled the same polynomial is evaluated
+/// in f64 instead. The public API is unchanged: callers still pass and
+/// receive `f32`, only the internal computation widens.
+#[cfg(feature = "correction_f64")]
 pub(crate) fn correct_high_lux(lux: f32) -> f32 {
-    lux.powf(4.0) * C3 + lux.powf(3.0) * C2 + lux * lux * C1 + lux * C0
+    const C0: f64 = 1.0023;
+    const C1: f64 = 8.1488e-05;
+    const C2: f64 = -9.3924e-09;
+    const C3: f64 = 6.0135e-13;
+
+    let lux = lux as f64;
+    ((((C3 * lux + C2) * lux + C1) * lux + C0) * lux) as f32
+}
+
+/// Inverse of [`correct_high_lux`], found via Newton–Raphson iteration.
+///
+/// `correct_high_lux` is strictly monotonic over the sensor's positive lux
+/// range, so rather than solving the quartic `f(x) - y = 0` in closed form
+/// (fragile: it cancels large and small coefficients against each other and
+/// is prone to underflow/overflow), we iterate
+/// `x_{n+1} = x_n - f(x_n) / f'(x_n)` starting from `x0 = y`. Near `y`'s
+/// lower end `C0 ≈ 1.0` makes this a good initial guess and convergence
+/// lands in 3-4 iterations; near the top of the sensor's range (~120,000 lx)
+/// the quartic term dominates, `x0` starts far from the root, and it takes
+/// closer to 10. The loop is capped at 12 to cover that full range.
+#[cfg(not(feature = "correction_f64"))]
+fn inverse_high_lux_correction(lux: f32) -> f32 {
+    let mut x = lux;
+    for _ in 0..12 {
+        let fx = correct_high_lux(x) - lux;
+        if fx.abs() < 1e-3 {
+            break;
+        }
+        let fx_prime = ((4.0 * C3 * x + 3.0 * C2) * x + 2.0 * C1) * x + C0;
+        x -= fx / fx_prime;
+        x = x.max(0.0);
+    }
+    x
 }
 
+/// f64 counterpart of [`inverse_high_lux_correction`].
+#[cfg(feature = "correction_f64")]
 fn inverse_high_lux_correction(lux: f32) -> f32 {
-    // Inverse of the polynomial used to correct for lux > 1000.
-    // `y = 6.0135e-13*(x^4) - 9.3924e-9*(x^3) + 8.1488e-5*(x^2) + 1.0023*x`.
-    // This runs into underflow/overflow issues if trying to solve it directly.
-    // However, it can be solved for unknown coefficients and then
-    // we put in the values.
-    -C2 / (4.0 * C3)
-        - (C2.powf(2.0) / (4.0 * C3.powf(2.0)) - (2.0 * C1) / (3.0 * C3)
-            + (2.0_f32.powf(1.0 / 3.0) * (C1.powf(2.0) - 3.0 * C2 * C0 - 12.0 * C3 * lux))
-                / (3.0
-                    * C3
-                    * (2.0 * C1.powf(3.0) - 9.0 * C2 * C1 * C0 + 27.0 * C3 * C0.powf(2.0)
-                        - 27.0 * C2.powf(2.0) * lux
-                        + 72.0 * C3 * C1 * lux
-                        + (-4.0 * (C1.powf(2.0) - 3.0 * C2 * C0 - 12.0 * C3 * lux).powf(3.0)
-                            + (2.0 * C1.powf(3.0) - 9.0 * C2 * C1 * C0
-                                + 27.0 * C3 * C0.powf(2.0)
-                                - 27.0 * C2.powf(2.0) * lux
-                                + 72.0 * C3 * C1 * lux)
-                                .powf(2.0))
-                        .sqrt())
-                    .powf(1.0 / 3.0))
-            + (2.0 * C1.powf(3.0) - 9.0 * C2 * C1 * C0 + 27.0 * C3 * C0.powf(2.0)
-                - 27.0 * C2.powf(2.0) * lux
-                + 72.0 * C3 * C1 * lux
-                + (-4.0 * (C1.powf(2.0) - 3.0 * C2 * C0 - 12.0 * C3 * lux).powf(3.0)
-                    + (2.0 * C1.powf(3.0) - 9.0 * C2 * C1 * C0 + 27.0 * C3 * C0.powf(2.0)
-                        - 27.0 * C2.powf(2.0) * lux
-                        + 72.0 * C3 * C1 * lux)
-                        .powf(2.0))
-                .sqrt())
-            .powf(1.0 / 3.0)
-                / (3.0 * 2.0_f32.powf(1.0 / 3.0) * C3))
-            .sqrt()
-            / 2.0
-        + (C2.powf(2.0) / (2.0 * C3.powf(2.0))
-            - (4.0 * C1) / (3.0 * C3)
-            - (2.0_f32.powf(1.0 / 3.0) * (C1.powf(2.0) - 3.0 * C2 * C0 - 12.0 * C3 * lux))
-                / (3.0
-                    * C3
-                    * (2.0 * C1.powf(3.0) - 9.0 * C2 * C1 * C0 + 27.0 * C3 * C0.powf(2.0)
-                        - 27.0 * C2.powf(2.0) * lux
-                        + 72.0 * C3 * C1 * lux
-                        + (-4.0 * (C1.powf(2.0) - 3.0 * C2 * C0 - 12.0 * C3 * lux).powf(3.0)
-                            + (2.0 * C1.powf(3.0) - 9.0 * C2 * C1 * C0
-                                + 27.0 * C3 * C0.powf(2.0)
-                                - 27.0 * C2.powf(2.0) * lux
-                                + 72.0 * C3 * C1 * lux)
-                                .powf(2.0))
-                        .sqrt())
-                    .powf(1.0 / 3.0))
-            - (2.0 * C1.powf(3.0) - 9.0 * C2 * C1 * C0 + 27.0 * C3 * C0.powf(2.0)
-                - 27.0 * C2.powf(2.0) * lux
-                + 72.0 * C3 * C1 * lux
-                + (-4.0 * (C1.powf(2.0) - 3.0 * C2 * C0 - 12.0 * C3 * lux).powf(3.0)
-                    + (2.0 * C1.powf(3.0) - 9.0 * C2 * C1 * C0 + 27.0 * C3 * C0.powf(2.0)
-                        - 27.0 * C2.powf(2.0) * lux
-                        + 72.0 * C3 * C1 * lux)
-                        .powf(2.0))
-                .sqrt())
-            .powf(1.0 / 3.0)
-                / (3.0 * 2.0_f32.powf(1.0 / 3.0) * C3)
-            - (-(C2.powf(3.0) / C3.powf(3.0)) + (4.0 * C2 * C1) / C3.powf(2.0) - (8.0 * C0) / C3)
-                / (4.0
-                    * (C2.powf(2.0) / (4.0 * C3.powf(2.0)) - (2.0 * C1) / (3.0 * C3)
-                        + (2.0_f32.powf(1.0 / 3.0)
-                            * (C1.powf(2.0) - 3.0 * C2 * C0 - 12.0 * C3 * lux))
-                            / (3.0
-                                * C3
-                                * (2.0 * C1.powf(3.0) - 9.0 * C2 * C1 * C0
-                                    + 27.0 * C3 * C0.powf(2.0)
-                                    - 27.0 * C2.powf(2.0) * lux
-                                    + 72.0 * C3 * C1 * lux
-                                    + (-4.0
-                                        * (C1.powf(2.0) - 3.0 * C2 * C0 - 12.0 * C3 * lux)
-                                            .powf(3.0)
-                                        + (2.0 * C1.powf(3.0) - 9.0 * C2 * C1 * C0
-                                            + 27.0 * C3 * C0.powf(2.0)
-                                            - 27.0 * C2.powf(2.0) * lux
-                                            + 72.0 * C3 * C1 * lux)
-                                            .powf(2.0))
-                                    .sqrt())
-                                .powf(1.0 / 3.0))
-                        + (2.0 * C1.powf(3.0) - 9.0 * C2 * C1 * C0 + 27.0 * C3 * C0.powf(2.0)
-                            - 27.0 * C2.powf(2.0) * lux
-                            + 72.0 * C3 * C1 * lux
-                            + (-4.0
-                                * (C1.powf(2.0) - 3.0 * C2 * C0 - 12.0 * C3 * lux).powf(3.0)
-                                + (2.0 * C1.powf(3.0) - 9.0 * C2 * C1 * C0
-                                    + 27.0 * C3 * C0.powf(2.0)
-                                    - 27.0 * C2.powf(2.0) * lux
-                                    + 72.0 * C3 * C1 * lux)
-                                    .powf(2.0))
-                            .sqrt())
-                        .powf(1.0 / 3.0)
-                            / (3.0 * 2.0_f32.powf(1.0 / 3.0) * C3))
-                        .sqrt()))
-        .sqrt()
-            / 2.0
+    const C0: f64 = 1.0023;
+    const C1: f64 = 8.1488e-05;
+    const C2: f64 = -9.3924e-09;
+    const C3: f64 = 6.0135e-13;
+
+    let y = lux as f64;
+    let mut x = y;
+    for _ in 0..12 {
+        let fx = ((((C3 * x + C2) * x + C1) * x + C0) * x) - y;
+        if fx.abs() < 1e-3 {
+            break;
+        }
+        let fx_prime = ((4.0 * C3 * x + 3.0 * C2) * x + 2.0 * C1) * x + C0;
+        x -= fx / fx_prime;
+        x = x.max(0.0);
+    }
+    x as f32
+}
+
+/// A precomputed table of raw threshold values for a fixed
+/// [`IntegrationTime`]/[`Gain`] pair.
+///
+/// [`calculate_raw_threshold_value`] re-runs the high-lux correction (and
+/// its iterative inverse) on every call, which is wasteful for callers who
+/// reprogram the ALS window thresholds often, e.g. to track a moving
+/// baseline. Build a table once from a fixed set of lux breakpoints and
+/// look up [`raw_for_lux`](Self::raw_for_lux) instead, which is just a
+/// linear scan and an interpolation.
+pub struct ThresholdTable<const N: usize> {
+    lux: [f32; N],
+    raw: [u16; N],
+}
+
+impl<const N: usize> ThresholdTable<N> {
+    /// Precompute the raw threshold value for each lux breakpoint.
+    ///
+    /// `breakpoints` must be sorted in ascending order; [`raw_for_lux`](Self::raw_for_lux)
+    /// does not validate this. Repeated values are tolerated (`raw_for_lux`
+    /// stops at the first breakpoint not below `lux`, so it never
+    /// interpolates across a repeated pair), but breakpoints that decrease
+    /// will produce nonsensical lookups.
+    pub fn build(it: IntegrationTime, gain: Gain, breakpoints: [f32; N]) -> Self {
+        let mut raw = [0u16; N];
+        for (slot, &lux) in raw.iter_mut().zip(breakpoints.iter()) {
+            *slot = calculate_raw_threshold_value(it, gain, lux);
+        }
+        Self {
+            lux: breakpoints,
+            raw,
+        }
+    }
+
+    /// Look up the raw threshold for `lux`, interpolating linearly between
+    /// the two nearest precomputed breakpoints.
+    ///
+    /// `lux` values outside the breakpoint range clamp to the nearest
+    /// endpoint's raw value.
+    pub fn raw_for_lux(&self, lux: f32) -> u16 {
+        if N == 0 {
+            return 0;
+        }
+        if lux <= self.lux[0] {
+            return self.raw[0];
+        }
+        if lux >= self.lux[N - 1] {
+            return self.raw[N - 1];
+        }
+        for i in 1..N {
+            if lux <= self.lux[i] {
+                let (x0, x1) = (self.lux[i - 1], self.lux[i]);
+                let (y0, y1) = (self.raw[i - 1] as f32, self.raw[i] as f32);
+                let t = (lux - x0) / (x1 - x0);
+                return (y0 + t * (y1 - y0)) as u16;
+            }
+        }
+        self.raw[N - 1]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `inverse_high_lux_correction` takes a *target corrected* lux value (what
+    // `calculate_raw_threshold_value`'s caller asks for) and solves for the
+    // raw lux that `correct_high_lux` would map back onto it, so round-trips
+    // must go target -> raw -> target, not raw -> target -> raw; the latter
+    // feeds values far outside the sensor's range into the inverse.
+    #[test]
+    fn inverse_round_trips_with_correct_high_lux() {
+        for &target in &[1000.1, 2000.0, 5000.0, 10_000.0, 50_000.0, 120_000.0] {
+            let raw = inverse_high_lux_correction(target);
+            let recovered = correct_high_lux(raw);
+            assert!(
+                (recovered - target).abs() < 1.0,
+                "target = {}, raw = {}, recovered = {}",
+                target,
+                raw,
+                recovered
+            );
+        }
+    }
+
+    #[test]
+    fn inverse_is_continuous_just_above_1000_lux() {
+        // `correct_high_lux` is close to the identity near its lower bound,
+        // so the inverse shouldn't jump around right where it starts being
+        // applied in `calculate_raw_threshold_value`.
+        let below = inverse_high_lux_correction(999.0);
+        let above = inverse_high_lux_correction(1000.1);
+        assert!((above - below).abs() < 2.0, "below = {}, above = {}", below, above);
+    }
+
+    #[test]
+    fn inverse_converges_within_iteration_cap_near_sensor_top() {
+        // Near the top of the sensor's low-gain range (~120,000 lx, direct
+        // sunlight), where C3's quartic term matters most and convergence
+        // is hardest.
+        let target = 120_000.0;
+        let raw = inverse_high_lux_correction(target);
+        let recovered = correct_high_lux(raw);
+        assert!(
+            (recovered - target).abs() < 1.0,
+            "target = {}, raw = {}, recovered = {}",
+            target,
+            raw,
+            recovered
+        );
+    }
+
+    #[test]
+    fn threshold_table_clamps_below_first_breakpoint() {
+        let table = ThresholdTable::build(IntegrationTime::_100ms, Gain::One, [100.0, 500.0, 1000.0]);
+        assert_eq!(table.raw_for_lux(0.0), table.raw_for_lux(100.0));
+    }
+
+    #[test]
+    fn threshold_table_clamps_above_last_breakpoint() {
+        let table = ThresholdTable::build(IntegrationTime::_100ms, Gain::One, [100.0, 500.0, 1000.0]);
+        assert_eq!(table.raw_for_lux(5000.0), table.raw_for_lux(1000.0));
+    }
+
+    #[test]
+    fn threshold_table_interpolates_between_breakpoints() {
+        let table = ThresholdTable::build(IntegrationTime::_100ms, Gain::One, [0.0, 1000.0]);
+        let direct = calculate_raw_threshold_value(IntegrationTime::_100ms, Gain::One, 500.0);
+        let interpolated = table.raw_for_lux(500.0);
+        // Below 1000 lx there's no correction applied, so the relationship is
+        // linear and the table's interpolation should match a direct lookup
+        // to within integer rounding.
+        assert!(
+            (interpolated as i32 - direct as i32).abs() <= 1,
+            "direct = {}, interpolated = {}",
+            direct,
+            interpolated
+        );
+    }
+
+    #[test]
+    fn threshold_table_empty_returns_zero() {
+        let table = ThresholdTable::build(IntegrationTime::_100ms, Gain::One, []);
+        assert_eq!(table.raw_for_lux(500.0), 0);
+    }
+
+    #[test]
+    fn threshold_table_tolerates_duplicate_breakpoints() {
+        // A repeated breakpoint can never be the pair an interpolation
+        // divides between: reaching bracket [i-1, i] in the lookup already
+        // requires `lux > self.lux[i-1]`, which is incompatible with
+        // `self.lux[i-1] == self.lux[i]` and `lux <= self.lux[i]` both
+        // holding, so this can't divide by zero regardless of ordering.
+        let table = ThresholdTable::build(
+            IntegrationTime::_100ms,
+            Gain::One,
+            [100.0, 500.0, 500.0, 1000.0],
+        );
+        let expected = ThresholdTable::build(IntegrationTime::_100ms, Gain::One, [100.0, 500.0, 1000.0])
+            .raw_for_lux(500.0);
+        assert_eq!(table.raw_for_lux(500.0), expected);
+    }
 }